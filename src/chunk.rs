@@ -0,0 +1,170 @@
+//! Content-defined chunking for reporting the *extent* of a change in a
+//! modified file, rather than just flagging that it changed.
+//!
+//! Each file is split into variable-length chunks using a rolling gear
+//! hash: a boundary falls wherever the low `TARGET_BITS` bits of the
+//! rolling hash are zero, which targets an average chunk size of
+//! `2^TARGET_BITS` bytes while staying aligned to content rather than fixed
+//! offsets, so a small edit only shifts the chunks around it. Chunk lists
+//! for the two files are then compared by their strong hashes: any chunk
+//! hash in the new file that isn't present in the old file is reported as
+//! changed.
+
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// Average chunk size is targeted at `2^TARGET_BITS` bytes (~8 KiB).
+const TARGET_BITS: u32 = 13;
+const MASK: u64 = (1 << TARGET_BITS) - 1;
+const MIN_CHUNK: usize = 2 * 1024;
+const MAX_CHUNK: usize = 64 * 1024;
+
+/// Separates a report line's path from its trailing chunk-diff annotation.
+/// Chosen because it can never appear in a filesystem path.
+pub const ANNOTATION_SEP: char = '\0';
+
+const fn gear_table() -> [u64; 256] {
+    let mut table = [0u64; 256];
+    let mut state: u64 = 0x9E3779B97F4A7C15;
+    let mut i = 0;
+    while i < 256 {
+        state = state
+            .wrapping_mul(0x2545_F491_4F6C_DD1D)
+            .wrapping_add(0x14057B7EF767814F);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+        z ^= z >> 31;
+        table[i] = z;
+        i += 1;
+    }
+    table
+}
+
+static GEAR: [u64; 256] = gear_table();
+
+struct Chunk {
+    hash: u64,
+    len: u64,
+}
+
+fn fnv1a(bytes: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for &b in bytes {
+        hash ^= b as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
+fn chunk_bytes(data: &[u8]) -> Vec<Chunk> {
+    let mut chunks = vec![];
+    let mut start = 0usize;
+    let mut h: u64 = 0;
+
+    for (i, &byte) in data.iter().enumerate() {
+        h = (h << 1).wrapping_add(GEAR[byte as usize]);
+        let len = i - start + 1;
+        if len >= MIN_CHUNK && (h & MASK == 0 || len >= MAX_CHUNK) {
+            chunks.push(Chunk {
+                hash: fnv1a(&data[start..=i]),
+                len: len as u64,
+            });
+            start = i + 1;
+            h = 0;
+        }
+    }
+    if start < data.len() {
+        chunks.push(Chunk {
+            hash: fnv1a(&data[start..]),
+            len: (data.len() - start) as u64,
+        });
+    }
+
+    chunks
+}
+
+/// Extent of the difference between two files' chunk lists.
+pub struct ChunkDiff {
+    pub total_chunks: usize,
+    pub changed_chunks: usize,
+    changed_bytes: u64,
+    total_bytes: u64,
+}
+
+impl ChunkDiff {
+    /// Percentage of `file2`'s bytes that fall in a chunk whose hash has no
+    /// match among `file1`'s chunks, rounded to the nearest whole percent.
+    pub fn changed_percent(&self) -> u64 {
+        (self.changed_bytes * 100 + self.total_bytes / 2).checked_div(self.total_bytes).unwrap_or(0)
+    }
+}
+
+fn diff_chunks(old: &[Chunk], new: &[Chunk]) -> ChunkDiff {
+    let old_hashes: std::collections::HashSet<u64> = old.iter().map(|c| c.hash).collect();
+    let total_bytes: u64 = new.iter().map(|c| c.len).sum();
+    let mut changed_bytes = 0u64;
+    let mut changed_chunks = 0usize;
+
+    for chunk in new {
+        if !old_hashes.contains(&chunk.hash) {
+            changed_bytes += chunk.len;
+            changed_chunks += 1;
+        }
+    }
+
+    ChunkDiff {
+        total_chunks: new.len(),
+        changed_chunks,
+        changed_bytes,
+        total_bytes,
+    }
+}
+
+/// Chunks `p1` and `p2` and reports how much of `p2` changed relative to
+/// `p1`, for a pair of files already known to differ.
+pub fn diff_files(p1: &Path, p2: &Path) -> io::Result<ChunkDiff> {
+    let data1 = fs::read(p1)?;
+    let data2 = fs::read(p2)?;
+    Ok(diff_chunks(&chunk_bytes(&data1), &chunk_bytes(&data2)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn identical_bytes_produce_no_changed_chunks() {
+        let data = b"the quick brown fox jumps over the lazy dog".repeat(200);
+        let diff = diff_chunks(&chunk_bytes(&data), &chunk_bytes(&data));
+        assert_eq!(diff.changed_chunks, 0);
+        assert_eq!(diff.changed_percent(), 0);
+    }
+
+    #[test]
+    fn a_single_insertion_only_changes_nearby_chunks() {
+        // Pseudo-random bytes, so content-defined boundaries actually occur
+        // (periodic data never hits one and is only cut at MAX_CHUNK).
+        let mut data1 = vec![0u8; 200_000];
+        let mut x: u32 = 1;
+        for b in data1.iter_mut() {
+            x = x.wrapping_mul(1103515245).wrapping_add(12345);
+            *b = (x >> 16) as u8;
+        }
+        let mut data2 = data1.clone();
+        data2.splice(100_000..100_000, std::iter::repeat_n(7u8, 37));
+
+        let diff = diff_chunks(&chunk_bytes(&data1), &chunk_bytes(&data2));
+        assert!(diff.changed_chunks > 0);
+        assert!(diff.changed_chunks < diff.total_chunks);
+        assert!(diff.changed_chunks <= 2);
+    }
+
+    #[test]
+    fn empty_file_has_no_chunks() {
+        let diff = diff_chunks(&chunk_bytes(&[]), &chunk_bytes(&[]));
+        assert_eq!(diff.total_chunks, 0);
+        assert_eq!(diff.changed_percent(), 0);
+    }
+}
@@ -0,0 +1,49 @@
+//! Minimal JSON string escaping for the `--json` report mode.
+//!
+//! diffrq has no JSON dependency, so this hand-rolls just enough escaping to
+//! safely embed arbitrary paths and error messages in NDJSON output.
+
+pub fn escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// Renders `value` as a quoted, escaped JSON string, or the literal `null`.
+pub fn quote_opt(value: Option<&str>) -> String {
+    match value {
+        Some(v) => format!("\"{}\"", escape(v)),
+        None => "null".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escapes_quotes_and_backslashes() {
+        assert_eq!(escape("a\"b\\c"), "a\\\"b\\\\c");
+    }
+
+    #[test]
+    fn escapes_control_characters() {
+        assert_eq!(escape("a\nb"), "a\\nb");
+    }
+
+    #[test]
+    fn quote_opt_handles_none_and_some() {
+        assert_eq!(quote_opt(None), "null");
+        assert_eq!(quote_opt(Some("x")), "\"x\"");
+    }
+}
@@ -0,0 +1,93 @@
+//! Parses the `LS_COLORS` environment variable so reported paths can be
+//! colorized by file type/extension the way `ls`, `fd`, and `exa` do,
+//! instead of diffrq's fixed per-status palette.
+
+use std::collections::HashMap;
+use std::path::Path;
+
+/// A compiled `LS_COLORS` lookup: file-type indicator codes (`di`, `ex`,
+/// `ln`, ...) and extension-glob codes (`*.rs=...`), each mapped to the raw
+/// SGR code(s) that follow `=` (e.g. `"01;32"`), without the escape wrapper.
+pub struct LsColors {
+    indicators: HashMap<String, String>,
+    extensions: HashMap<String, String>,
+}
+
+impl LsColors {
+    /// Parses `LS_COLORS` from the environment, if set.
+    pub fn from_env() -> Option<Self> {
+        std::env::var("LS_COLORS").ok().map(|spec| Self::parse(&spec))
+    }
+
+    fn parse(spec: &str) -> Self {
+        let mut indicators = HashMap::new();
+        let mut extensions = HashMap::new();
+
+        for entry in spec.split(':') {
+            let Some((key, code)) = entry.split_once('=') else {
+                continue;
+            };
+            if code.is_empty() {
+                continue;
+            }
+            if let Some(ext) = key.strip_prefix("*.") {
+                extensions.insert(ext.to_lowercase(), code.to_string());
+            } else if !key.starts_with('*') {
+                indicators.insert(key.to_string(), code.to_string());
+            }
+        }
+
+        Self { indicators, extensions }
+    }
+
+    /// The SGR code to colorize `path` with, if `LS_COLORS` defines one for
+    /// its type or extension. Directories and executables are matched by
+    /// indicator (`di`, `ex`); everything else by extension, falling back to
+    /// the plain-file indicator (`fi`).
+    pub fn code_for(&self, path: &Path, is_dir: bool, is_executable: bool) -> Option<&str> {
+        if is_dir {
+            return self.indicators.get("di").map(String::as_str);
+        }
+        if is_executable {
+            if let Some(code) = self.indicators.get("ex") {
+                return Some(code);
+            }
+        }
+        if let Some(ext) = path.extension().and_then(|e| e.to_str()) {
+            if let Some(code) = self.extensions.get(&ext.to_lowercase()) {
+                return Some(code);
+            }
+        }
+        self.indicators.get("fi").map(String::as_str)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_extension_case_insensitively() {
+        let lsc = LsColors::parse("*.rs=01;33:*.png=01;35");
+        assert_eq!(lsc.code_for(Path::new("a/b.RS"), false, false), Some("01;33"));
+        assert_eq!(lsc.code_for(Path::new("a/b.png"), false, false), Some("01;35"));
+    }
+
+    #[test]
+    fn directories_use_the_di_indicator() {
+        let lsc = LsColors::parse("di=01;34:*.rs=01;33");
+        assert_eq!(lsc.code_for(Path::new("a/b.rs"), true, false), Some("01;34"));
+    }
+
+    #[test]
+    fn executables_use_the_ex_indicator_over_extension() {
+        let lsc = LsColors::parse("ex=01;32:*.sh=01;33");
+        assert_eq!(lsc.code_for(Path::new("a/b.sh"), false, true), Some("01;32"));
+    }
+
+    #[test]
+    fn unknown_extension_falls_back_to_fi() {
+        let lsc = LsColors::parse("fi=00");
+        assert_eq!(lsc.code_for(Path::new("a/b.unknown"), false, false), Some("00"));
+    }
+}
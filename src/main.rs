@@ -1,31 +1,78 @@
+mod chunk;
+mod json;
+mod ls_colors;
+mod matcher;
+
 use std::{
     cell::RefCell,
     cmp::Ordering,
-    collections::HashSet,
     ffi::OsString,
     fs::{self, File},
     io::{self, BufReader, Read},
+    os::unix::fs::{MetadataExt, PermissionsExt},
     path::{Path, PathBuf},
 };
 
 use anyhow::{Context, Result};
+use rayon::prelude::*;
+
+use ls_colors::LsColors;
+use matcher::Matcher;
+
+/// The kind of filesystem entry, as reported by `symlink_metadata` (i.e.
+/// without following symlinks). A symlink is always its own kind here, even
+/// when it points at a directory, so a cyclic symlink is never mistaken for
+/// a directory to recurse into.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FileKind {
+    Regular,
+    Directory,
+    Symlink,
+}
 
 #[derive(Debug, Clone)]
 struct EntryInfo {
     path: PathBuf,
     file_name: OsString,
     is_dir: bool,
+    kind: FileKind,
     len: u64,
+    mode: u32,
+    uid: u32,
+    gid: u32,
+    mtime: i64,
+    symlink_target: Option<PathBuf>,
 }
 
 impl EntryInfo {
     fn from_dir_entry(entry: fs::DirEntry) -> Result<Self> {
+        // `DirEntry::metadata` mirrors `symlink_metadata` on Unix, so a
+        // symlink is reported as itself rather than as whatever it points to.
         let metadata = entry.metadata()?;
+        let path = entry.path();
+        let kind = if metadata.file_type().is_symlink() {
+            FileKind::Symlink
+        } else if metadata.is_dir() {
+            FileKind::Directory
+        } else {
+            FileKind::Regular
+        };
+        let symlink_target = if kind == FileKind::Symlink {
+            fs::read_link(&path).ok()
+        } else {
+            None
+        };
         Ok(Self {
-            path: entry.path(),
             file_name: entry.file_name(),
-            is_dir: entry.path().is_dir(),
+            is_dir: kind == FileKind::Directory,
             len: metadata.len(),
+            mode: metadata.permissions().mode(),
+            uid: metadata.uid(),
+            gid: metadata.gid(),
+            mtime: metadata.mtime(),
+            symlink_target,
+            kind,
+            path,
         })
     }
 }
@@ -69,14 +116,164 @@ fn files_are_identical(p1: &Path, p2: &Path) -> io::Result<bool> {
     })
 }
 
-fn compare_directories<F>(dir1: &Path, dir2: &Path, excludes: &HashSet<OsString>, all_mode: bool, report: &F) -> Result<()>
-where
-    F: Fn(&str),
-{
+/// One slot of the sorted merge between `dir1` and `dir2`, not yet resolved
+/// into report lines. Keeping these around (instead of reporting inline)
+/// lets us resolve the slots in parallel while still flushing them in the
+/// original sorted order.
+enum PendingEntry {
+    Added(EntryInfo),
+    Deleted(EntryInfo),
+    TypeChanged { old: EntryInfo, new: EntryInfo },
+    DifferentLen(EntryInfo, EntryInfo),
+    MaybeModified(EntryInfo, EntryInfo),
+    Dir(PathBuf, PathBuf, String),
+}
+
+/// `P:`/`T:` lines for a pair of entries whose content (or symlink target)
+/// already matches but whose permissions/ownership or mtime differ. Only
+/// emitted in `--metadata` mode.
+fn metadata_tags(a: &EntryInfo, b: &EntryInfo) -> Vec<String> {
+    let mut tags = vec![];
+    if a.mode != b.mode || a.uid != b.uid || a.gid != b.gid {
+        tags.push(format!("P:{}", b.path.to_string_lossy()));
+    }
+    if a.mtime != b.mtime {
+        tags.push(format!("T:{}", b.path.to_string_lossy()));
+    }
+    tags
+}
+
+/// An `E:` note when `entry` is a symlink whose target can't be resolved.
+fn broken_symlink_note(entry: &EntryInfo) -> Option<String> {
+    if entry.kind == FileKind::Symlink && fs::metadata(&entry.path).is_err() {
+        Some(format!(
+            "E:Broken symlink: {} -> {}",
+            entry.path.display(),
+            entry
+                .symlink_target
+                .as_deref()
+                .unwrap_or_else(|| Path::new("?"))
+                .display()
+        ))
+    } else {
+        None
+    }
+}
+
+/// Formats the `M:` line for a file known to differ, appending a
+/// `chunk::ANNOTATION_SEP`-separated chunk-diff annotation when
+/// `chunk_mode` is enabled.
+fn modified_line(p1: &Path, p2: &Path, chunk_mode: bool) -> String {
+    if !chunk_mode {
+        return format!("M:{}", p2.to_string_lossy());
+    }
+    match chunk::diff_files(p1, p2) {
+        Ok(diff) => format!(
+            "M:{}{}({} of {} chunks, {}% changed)",
+            p2.to_string_lossy(),
+            chunk::ANNOTATION_SEP,
+            diff.changed_chunks,
+            diff.total_chunks,
+            diff.changed_percent()
+        ),
+        Err(_) => format!("M:{}", p2.to_string_lossy()),
+    }
+}
+
+fn resolve_entry(
+    entry: &PendingEntry,
+    matcher: &Matcher,
+    all_mode: bool,
+    chunk_mode: bool,
+    metadata_mode: bool,
+) -> Vec<String> {
+    match entry {
+        PendingEntry::Added(e) => vec![format!("A:{}", e.path.to_string_lossy())],
+        PendingEntry::Deleted(e) => vec![format!("D:{}", e.path.to_string_lossy())],
+        PendingEntry::TypeChanged { old, new } => vec![
+            format!("D:{}", old.path.to_string_lossy()),
+            format!("A:{}", new.path.to_string_lossy()),
+        ],
+        PendingEntry::DifferentLen(a, b) => vec![modified_line(&a.path, &b.path, chunk_mode)],
+        PendingEntry::MaybeModified(a, b) if a.kind == FileKind::Symlink => {
+            let mut lines: Vec<String> = [broken_symlink_note(a), broken_symlink_note(b)]
+                .into_iter()
+                .flatten()
+                .collect();
+            if a.symlink_target != b.symlink_target {
+                lines.push(format!("M:{}", b.path.to_string_lossy()));
+                return lines;
+            }
+            if metadata_mode {
+                lines.extend(metadata_tags(a, b));
+            }
+            if lines.is_empty() && all_mode {
+                lines.push(format!("-:{}", b.path.to_string_lossy()));
+            }
+            lines
+        }
+        PendingEntry::MaybeModified(a, b) => {
+            let identical = if a.len == 0 {
+                Ok(true)
+            } else {
+                files_are_identical(&a.path, &b.path)
+            };
+            match identical {
+                Ok(false) => vec![modified_line(&a.path, &b.path, chunk_mode)],
+                Ok(true) => {
+                    let mut lines = if metadata_mode { metadata_tags(a, b) } else { vec![] };
+                    if lines.is_empty() && all_mode {
+                        lines.push(format!("-:{}", b.path.to_string_lossy()));
+                    }
+                    lines
+                }
+                Err(e) => vec![format!(
+                    "E:Failed to compare '{}' and '{}': {}",
+                    a.path.display(),
+                    b.path.display(),
+                    e
+                )],
+            }
+        }
+        PendingEntry::Dir(d1, d2, rel) => {
+            match compare_directories(d1, d2, rel, matcher, all_mode, chunk_mode, metadata_mode) {
+                Ok(lines) => lines,
+                Err(e) => vec![format!(
+                    "E:error comparing subdirectories {} and {}: {}",
+                    d1.display(),
+                    d2.display(),
+                    e
+                )],
+            }
+        }
+    }
+}
+
+/// Compares `dir1` against `dir2` and returns the report lines for this
+/// directory (including lines from recursively compared subdirectories,
+/// spliced in at the right position) in the same order a purely sequential
+/// walk would have produced them. Independent slots of the sorted merge are
+/// resolved across the global rayon pool, reusing `THREAD_BUFFERS` on
+/// whichever worker thread picks them up.
+fn compare_directories(
+    dir1: &Path,
+    dir2: &Path,
+    rel_prefix: &str,
+    matcher: &Matcher,
+    all_mode: bool,
+    chunk_mode: bool,
+    metadata_mode: bool,
+) -> Result<Vec<String>> {
     let read_entries = |dir: &Path| -> Result<Vec<EntryInfo>> {
         fs::read_dir(dir)?
             .filter_map(Result::ok)
-            .filter(|entry| !excludes.contains(&entry.file_name()))
+            .filter(|entry| {
+                // `file_type()` mirrors `symlink_metadata`, so a symlink to a
+                // directory is never treated as a directory here.
+                let is_dir = entry.file_type().map(|t| t.is_dir()).unwrap_or(false);
+                let rel = join_rel(rel_prefix, &entry.file_name().to_string_lossy());
+                !matcher.is_excluded(&rel, is_dir)
+            })
             .map(EntryInfo::from_dir_entry)
             .collect()
     };
@@ -87,8 +284,7 @@ where
     entries1.sort_by(|a, b| a.file_name.cmp(&b.file_name));
     entries2.sort_by(|a, b| a.file_name.cmp(&b.file_name));
 
-    let mut files_to_compare = vec![];
-    let mut dirs_to_compare = vec![];
+    let mut pending = vec![];
 
     let mut i1 = entries1.into_iter();
     let mut i2 = entries2.into_iter();
@@ -99,102 +295,102 @@ where
         match (e1.as_ref(), e2.as_ref()) {
             (Some(a), Some(b)) => match a.file_name.cmp(&b.file_name) {
                 Ordering::Less => {
-                    report(&format!("D:{}", a.path.to_string_lossy()));
+                    pending.push(PendingEntry::Deleted(a.clone()));
                     e1 = i1.next();
                 }
                 Ordering::Greater => {
-                    report(&format!("A:{}", b.path.to_string_lossy()));
+                    pending.push(PendingEntry::Added(b.clone()));
                     e2 = i2.next();
                 }
                 Ordering::Equal => {
-                    if a.is_dir != b.is_dir {
-                        report(&format!("D:{}", a.path.to_string_lossy()));
-                        report(&format!("A:{}", b.path.to_string_lossy()));
+                    if a.kind != b.kind {
+                        pending.push(PendingEntry::TypeChanged {
+                            old: a.clone(),
+                            new: b.clone(),
+                        });
                     } else if a.is_dir {
-                        dirs_to_compare.push((a.path.clone(), b.path.clone()));
+                        let rel = join_rel(rel_prefix, &a.file_name.to_string_lossy());
+                        pending.push(PendingEntry::Dir(a.path.clone(), b.path.clone(), rel));
+                    } else if a.kind == FileKind::Symlink {
+                        pending.push(PendingEntry::MaybeModified(a.clone(), b.clone()));
                     } else if a.len != b.len {
-                        report(&format!("M:{}", b.path.to_string_lossy()));
-                    } else if a.len > 0 {
-                        if all_mode {
-                            // report immediately in "--all-mode" to keep the order of files
-                            let p1 = &a.path.clone();
-                            let p2 = &b.path.clone();
-                            match files_are_identical(&p1, &p2) {
-                                Ok(false) => report(&format!("M:{}", p2.to_string_lossy())),
-                                Ok(true) => if all_mode { report(&format!("-:{}", p2.to_string_lossy())) },
-                                Err(e) => report(&format!(
-                                        "E:Failed to compare '{}' and '{}': {}",
-                                        p1.display(),
-                                        p2.display(),
-                                        e
-                                )),
-                            }
-                        } else {
-                            files_to_compare.push((a.path.clone(), b.path.clone()));
-                        }
+                        pending.push(PendingEntry::DifferentLen(a.clone(), b.clone()));
+                    } else {
+                        pending.push(PendingEntry::MaybeModified(a.clone(), b.clone()));
                     }
                     e1 = i1.next();
                     e2 = i2.next();
                 }
             },
             (Some(a), None) => {
-                report(&format!("D:{}", a.path.to_string_lossy()));
+                pending.push(PendingEntry::Deleted(a.clone()));
                 e1 = i1.next();
             }
             (None, Some(b)) => {
-                report(&format!("A:{}", b.path.to_string_lossy()));
+                pending.push(PendingEntry::Added(b.clone()));
                 e2 = i2.next();
             }
             _ => break,
         }
     }
 
-    for (p1, p2) in files_to_compare {
-        match files_are_identical(&p1, &p2) {
-            Ok(false) => report(&format!("M:{}", p2.to_string_lossy())),
-            Ok(true) => if all_mode { report(&format!("-:{}", p2.to_string_lossy())) },
-            Err(e) => report(&format!(
-                "E:Failed to compare '{}' and '{}': {}",
-                p1.display(),
-                p2.display(),
-                e
-            )),
-        }
-    }
+    let lines = pending
+        .par_iter()
+        .map(|entry| resolve_entry(entry, matcher, all_mode, chunk_mode, metadata_mode))
+        .collect::<Vec<_>>();
 
-    for (d1, d2) in dirs_to_compare {
-        if let Err(e) = compare_directories(&d1, &d2, excludes, all_mode, report) {
-            report(&format!(
-                "E:error comparing subdirectories {} and {}: {}",
-                d1.display(),
-                d2.display(),
-                e
-            ));
-        }
-    }
+    Ok(lines.into_iter().flatten().collect())
+}
 
-    Ok(())
+fn join_rel(prefix: &str, name: &str) -> String {
+    if prefix.is_empty() {
+        name.to_string()
+    } else {
+        format!("{prefix}/{name}")
+    }
 }
 
 fn main() -> Result<()> {
     let mut all_mode = false;
     let mut noformat_mode = false;
+    let mut chunk_mode = false;
+    let mut metadata_mode = false;
+    let mut json_mode = false;
+    let mut jobs = None;
     let mut args = std::env::args().skip(1);
     let mut dir1 = None;
     let mut dir2 = None;
-    let mut excludes = HashSet::new();
+    let mut matcher = Matcher::new();
 
     while let Some(arg) = args.next() {
         if arg == "--all" {
             all_mode = true;
         } else if arg == "--noformat" {
             noformat_mode = true;
+        } else if arg == "--chunks" {
+            chunk_mode = true;
+        } else if arg == "--metadata" {
+            metadata_mode = true;
+        } else if arg == "--json" {
+            json_mode = true;
         } else if arg == "--exclude" {
             if let Some(value) = args.next() {
-                excludes.insert(OsString::from(value));
+                matcher.add_pattern(&value);
             } else {
                 anyhow::bail!("Missing value after --exclude");
             }
+        } else if arg == "--exclude-from" || arg == "--ignore-file" {
+            if let Some(value) = args.next() {
+                matcher.merge(Matcher::load_file(Path::new(&value))?);
+            } else {
+                anyhow::bail!("Missing value after {}", arg);
+            }
+        } else if arg == "--jobs" {
+            if let Some(value) = args.next() {
+                jobs = Some(value.parse::<usize>().context("Invalid value for --jobs")?);
+            } else {
+                anyhow::bail!("Missing value after --jobs");
+            }
         } else if !arg.starts_with('-') {
             if dir1.is_none() {
                 dir1 = Some(PathBuf::from(arg));
@@ -217,21 +413,81 @@ fn main() -> Result<()> {
         }
     }
 
+    if let Some(jobs) = jobs {
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(jobs)
+            .build_global()
+            .context("Failed to set up the worker pool")?;
+    }
+
     let dir1_ref = dir1.as_path();
     let dir2_ref = dir2.as_path();
+    let ls_colors = LsColors::from_env();
 
     let report_fn = |line: &str| {
         if let Some((tag, raw_path)) = line.split_once(':') {
+            let (raw_path, annotation) = match raw_path.split_once(chunk::ANNOTATION_SEP) {
+                Some((path, annotation)) => (path, Some(annotation)),
+                None => (raw_path, None),
+            };
             let full_path = Path::new(raw_path);
             let reduced = match tag {
-                "M" | "A" | "-" => full_path.strip_prefix(dir2_ref).unwrap_or(full_path),
+                "M" | "A" | "-" | "P" | "T" => full_path.strip_prefix(dir2_ref).unwrap_or(full_path),
                 "D" => full_path.strip_prefix(dir1_ref).unwrap_or(full_path),
                 _ => full_path,
             };
 
-            let is_dir = full_path.is_dir();
+            // Check the link itself, not its target, so a symlink to a
+            // directory isn't displayed with a trailing `/`.
+            let is_dir = fs::symlink_metadata(full_path)
+                .map(|m| m.is_dir())
+                .unwrap_or(false);
             let path_str = reduced.to_string_lossy();
-            let display_path = format!("{path_str}{}", if is_dir { "/" } else { "" });
+
+            if json_mode {
+                let status = match tag {
+                    "M" => "modified",
+                    "A" => "added",
+                    "D" => "deleted",
+                    "-" => "unchanged",
+                    "P" => "permission_changed",
+                    "T" => "mtime_changed",
+                    "E" => "error",
+                    _ => return,
+                };
+                if tag == "E" {
+                    println!(
+                        "{{\"status\":\"error\",\"path\":null,\"is_dir\":false,\"size_delta\":null,\"message\":{}}}",
+                        json::quote_opt(Some(raw_path))
+                    );
+                    return;
+                }
+                // `reduced` is relative to whichever root `tag` was stripped
+                // against; joining it onto the other root recovers the
+                // corresponding path there, letting us compute a size delta
+                // without having threaded it through the report pipeline.
+                let size_delta = if tag == "M" {
+                    let old_path = dir1_ref.join(reduced);
+                    match (fs::metadata(full_path), fs::metadata(&old_path)) {
+                        (Ok(new_meta), Ok(old_meta)) => {
+                            Some(new_meta.len() as i64 - old_meta.len() as i64)
+                        }
+                        _ => None,
+                    }
+                } else {
+                    None
+                };
+                println!(
+                    "{{\"status\":\"{status}\",\"path\":{},\"is_dir\":{is_dir},\"size_delta\":{},\"note\":{},\"message\":null}}",
+                    json::quote_opt(Some(&path_str)),
+                    size_delta.map(|d| d.to_string()).unwrap_or_else(|| "null".to_string()),
+                    json::quote_opt(annotation.map(str::trim)),
+                );
+                return;
+            }
+
+            let suffix = annotation.map(|a| format!(" {a}")).unwrap_or_default();
+            let display_path = format!("{path_str}{}{suffix}", if is_dir { "/" } else { "" });
 
             if noformat_mode {
                 match tag {
@@ -239,15 +495,45 @@ fn main() -> Result<()> {
                     "A" => println!("A: {display_path}"),
                     "D" => println!("D: {display_path}"),
                     "-" => println!("-: {display_path}"),
+                    "P" => println!("P: {display_path}"),
+                    "T" => println!("T: {display_path}"),
                     "E" => eprintln!("Error: {display_path}"),
                     _ => {}
                 }
             } else {
+                // The status box keeps diffrq's fixed per-status color; the
+                // path itself is colorized by file type via LS_COLORS when
+                // set, falling back to the same fixed color otherwise.
+                let fallback_code = match tag {
+                    "M" => "34",
+                    "A" => "32",
+                    "D" => "31",
+                    "P" => "35",
+                    "T" => "36",
+                    _ => "",
+                };
+                let path_code = ls_colors
+                    .as_ref()
+                    .and_then(|lsc| {
+                        let executable = fs::symlink_metadata(full_path)
+                            .map(|m| m.permissions().mode() & 0o111 != 0)
+                            .unwrap_or(false);
+                        lsc.code_for(full_path, is_dir, executable)
+                    })
+                    .unwrap_or(fallback_code);
+                let colored_path = if path_code.is_empty() {
+                    display_path.clone()
+                } else {
+                    format!("\x1b[{path_code}m{display_path}\x1b[0m")
+                };
+
                 match tag {
-                    "M" => println!("M │\x1b[34m▮▮\x1b[0m│ \x1b[34m{display_path}\x1b[0m"),
-                    "A" => println!("A │\x1b[32m ▮\x1b[0m│ \x1b[32m{display_path}\x1b[0m"),
-                    "D" => println!("D │\x1b[31m▮ \x1b[0m│ \x1b[31m{display_path}\x1b[0m"),
-                    "-" => println!("- │▮▮│ {display_path}"),
+                    "M" => println!("M │\x1b[34m▮▮\x1b[0m│ {colored_path}"),
+                    "A" => println!("A │\x1b[32m ▮\x1b[0m│ {colored_path}"),
+                    "D" => println!("D │\x1b[31m▮ \x1b[0m│ {colored_path}"),
+                    "-" => println!("- │▮▮│ {colored_path}"),
+                    "P" => println!("P │\x1b[35m▮▮\x1b[0m│ {colored_path}"),
+                    "T" => println!("T │\x1b[36m▮▮\x1b[0m│ {colored_path}"),
                     "E" => eprintln!("\x1b[91mError: {display_path}\x1b[0m"),
                     _ => {}
                 }
@@ -255,7 +541,10 @@ fn main() -> Result<()> {
         }
     };
 
-    compare_directories(dir1_ref, dir2_ref, &excludes, all_mode, &report_fn)?;
+    let lines = compare_directories(dir1_ref, dir2_ref, "", &matcher, all_mode, chunk_mode, metadata_mode)?;
+    for line in &lines {
+        report_fn(line);
+    }
 
     Ok(())
 }
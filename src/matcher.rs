@@ -0,0 +1,199 @@
+//! Glob and `.gitignore`-style exclusion matching.
+//!
+//! Patterns are compiled once and then checked against each entry's path
+//! relative to the root being scanned, both while filtering a directory's
+//! entries and before recursing into a subdirectory so an entirely ignored
+//! tree can be pruned without ever being read.
+
+use std::fs;
+use std::path::Path;
+
+use anyhow::{Context, Result};
+
+struct Pattern {
+    glob: String,
+    anchored: bool,
+    dir_only: bool,
+    negate: bool,
+}
+
+/// A compiled set of exclusion patterns, in the order they were added.
+/// Later patterns take precedence over earlier ones (the last matching
+/// pattern wins), mirroring `.gitignore` semantics, including `!negated`
+/// patterns that re-include a path an earlier pattern excluded.
+#[derive(Default)]
+pub struct Matcher {
+    patterns: Vec<Pattern>,
+}
+
+impl Matcher {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a single `--exclude` style pattern.
+    pub fn add_pattern(&mut self, pattern: &str) {
+        if let Some(compiled) = compile_pattern(pattern) {
+            self.patterns.push(compiled);
+        }
+    }
+
+    /// Adds every pattern in `contents`, one per line, using `.gitignore`
+    /// conventions: blank lines and lines starting with `#` are skipped.
+    pub fn add_patterns_from_str(&mut self, contents: &str) {
+        for line in contents.lines() {
+            let line = line.trim_end();
+            if line.trim().is_empty() || line.trim_start().starts_with('#') {
+                continue;
+            }
+            self.add_pattern(line);
+        }
+    }
+
+    /// Loads patterns from an ignore file such as `--exclude-from <file>`.
+    pub fn load_file(path: &Path) -> Result<Self> {
+        let contents = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read ignore file: {}", path.display()))?;
+        let mut matcher = Self::new();
+        matcher.add_patterns_from_str(&contents);
+        Ok(matcher)
+    }
+
+    pub fn merge(&mut self, other: Matcher) {
+        self.patterns.extend(other.patterns);
+    }
+
+    /// Returns whether `rel_path` (relative to the scan root, using `/` as
+    /// the separator) should be excluded. `is_dir` lets directory-only
+    /// patterns (those ending in `/`) skip plain files.
+    pub fn is_excluded(&self, rel_path: &str, is_dir: bool) -> bool {
+        let mut excluded = false;
+        for pattern in &self.patterns {
+            if pattern.dir_only && !is_dir {
+                continue;
+            }
+            if pattern_matches(pattern, rel_path) {
+                excluded = !pattern.negate;
+            }
+        }
+        excluded
+    }
+}
+
+fn compile_pattern(raw: &str) -> Option<Pattern> {
+    let mut pattern = raw.trim();
+    if pattern.is_empty() {
+        return None;
+    }
+
+    let negate = if let Some(rest) = pattern.strip_prefix('!') {
+        pattern = rest;
+        true
+    } else {
+        false
+    };
+
+    let dir_only = pattern.ends_with('/');
+    if dir_only {
+        pattern = &pattern[..pattern.len() - 1];
+    }
+
+    let anchored = pattern.starts_with('/') || pattern.contains('/');
+    let glob = pattern.strip_prefix('/').unwrap_or(pattern).to_string();
+
+    Some(Pattern {
+        glob,
+        anchored,
+        dir_only,
+        negate,
+    })
+}
+
+fn pattern_matches(pattern: &Pattern, rel_path: &str) -> bool {
+    if pattern.anchored {
+        glob_match(&pattern.glob, rel_path)
+    } else {
+        let basename = rel_path.rsplit('/').next().unwrap_or(rel_path);
+        glob_match(&pattern.glob, basename) || glob_match(&pattern.glob, rel_path)
+    }
+}
+
+/// Matches `text` against a shell-style glob where `*` matches any run of
+/// characters except `/`, `**` matches any run of characters including `/`,
+/// and `?` matches a single non-`/` character.
+fn glob_match(glob: &str, text: &str) -> bool {
+    let g: Vec<char> = glob.chars().collect();
+    let t: Vec<char> = text.chars().collect();
+    glob_match_rec(&g, &t)
+}
+
+fn glob_match_rec(g: &[char], t: &[char]) -> bool {
+    match g.first() {
+        None => t.is_empty(),
+        Some('?') => !t.is_empty() && t[0] != '/' && glob_match_rec(&g[1..], &t[1..]),
+        Some('*') => {
+            if g.get(1) == Some(&'*') {
+                // `**` crosses path separators.
+                let mut rest = 2;
+                while g.get(rest) == Some(&'*') {
+                    rest += 1;
+                }
+                (0..=t.len()).any(|i| glob_match_rec(&g[rest..], &t[i..]))
+            } else {
+                (0..=t.len())
+                    .take_while(|&i| i == 0 || t[i - 1] != '/')
+                    .any(|i| glob_match_rec(&g[1..], &t[i..]))
+            }
+        }
+        Some(&c) => !t.is_empty() && t[0] == c && glob_match_rec(&g[1..], &t[1..]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matcher(patterns: &[&str]) -> Matcher {
+        let mut m = Matcher::new();
+        for p in patterns {
+            m.add_pattern(p);
+        }
+        m
+    }
+
+    #[test]
+    fn floating_glob_matches_any_depth() {
+        let m = matcher(&["*.tmp"]);
+        assert!(m.is_excluded("a.tmp", false));
+        assert!(m.is_excluded("sub/a.tmp", false));
+        assert!(!m.is_excluded("a.tmp.keep", false));
+    }
+
+    #[test]
+    fn anchored_pattern_matches_from_root_only() {
+        let m = matcher(&["/foo"]);
+        assert!(m.is_excluded("foo", false));
+        assert!(!m.is_excluded("sub/foo", false));
+    }
+
+    #[test]
+    fn double_star_crosses_directories() {
+        let m = matcher(&["build/**"]);
+        assert!(m.is_excluded("build/a/b/c.o", false));
+        assert!(!m.is_excluded("other/build/a", false));
+    }
+
+    #[test]
+    fn dir_only_pattern_skips_files() {
+        let m = matcher(&["target/"]);
+        assert!(m.is_excluded("target", true));
+        assert!(!m.is_excluded("target", false));
+    }
+
+    #[test]
+    fn negation_reincludes_a_path() {
+        let m = matcher(&["*.log", "!keep.log"]);
+        assert!(m.is_excluded("a.log", false));
+        assert!(!m.is_excluded("keep.log", false));
+    }
+}